@@ -13,6 +13,14 @@ pub enum ReaderError {
     MissingField { field: FieldType },
     #[error("Field not iterable")]
     FieldNotIterable,
+    #[error("Unexpected end of file at offset {offset} while reading field {field:?}")]
+    UnexpectedEof { offset: u64, field: FieldType },
+    #[error("Short read at offset {offset}: expected {expected} bytes, got {got}")]
+    ShortRead { offset: u64, expected: usize, got: usize },
+    #[error("Length prefix for field {field:?} at offset {offset} did not terminate within 5 bytes")]
+    InvalidLengthPrefix { offset: u64, field: FieldType },
+    #[error("Field {field:?} at offset {offset} declares a {len} byte string, exceeding the {max} byte limit")]
+    StringTooLong { offset: u64, field: FieldType, len: usize, max: usize },
     #[error(transparent)]
     Io(#[from] io::Error),
 }
@@ -1,3 +1,6 @@
+use std::ops::Index;
+use std::slice;
+
 use num_enum::TryFromPrimitive;
 
 #[derive(Debug, Eq, PartialEq, TryFromPrimitive, Clone, Copy)]
@@ -27,7 +30,8 @@ impl FieldType {
     pub fn get_data_type(&self) -> FieldFormat {
         match self {
             Self::SNPCount | Self::IlluminaID | Self::RedGreen => FieldFormat::Int,
-            Self::SD | Self::BeadCounts | Self::Mean => FieldFormat::Short,
+            Self::SD | Self::Mean => FieldFormat::Short,
+            Self::BeadCounts => FieldFormat::Byte,
             Self::Manifest
             | Self::Barcode
             | Self::Format
@@ -61,7 +65,7 @@ pub enum FieldValue {
     Long(i64),
     Short(u16),
     Int(i32),
-    Byte(Vec<u8>),
+    Byte(u8),
     RunInfo,
     MidBlock,
 }
@@ -78,6 +82,75 @@ pub struct Field {
     pub value: FieldValue,
 }
 
+/// The field directory read from an IDAT header, with typed lookup helpers in place
+/// of ad-hoc `.iter().find(...)` over a bare `Vec<FieldDef>`.
+#[derive(Debug)]
+pub struct FieldsInfo(Vec<FieldDef>);
+
+impl FieldsInfo {
+    pub fn get(&self, field: FieldType) -> Option<&FieldDef> {
+        self.0.iter().find(|f| f.field_type == field)
+    }
+
+    pub fn contains(&self, field: FieldType) -> bool {
+        self.get(field).is_some()
+    }
+
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    pub fn iter(&self) -> slice::Iter<'_, FieldDef> {
+        self.0.iter()
+    }
+}
+
+impl Index<FieldType> for FieldsInfo {
+    type Output = FieldDef;
+
+    fn index(&self, field: FieldType) -> &FieldDef {
+        self.get(field).expect("field not present in directory")
+    }
+}
+
+impl FromIterator<FieldDef> for FieldsInfo {
+    fn from_iter<T: IntoIterator<Item = FieldDef>>(iter: T) -> Self {
+        Self(iter.into_iter().collect())
+    }
+}
+
+impl<'a> IntoIterator for &'a FieldsInfo {
+    type Item = &'a FieldDef;
+    type IntoIter = slice::Iter<'a, FieldDef>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.0.iter()
+    }
+}
+
+/// A single SNP's fused intensity data: the four per-bead arrays read in lockstep.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SnpRecord {
+    pub illumina_id: i32,
+    pub mean: u16,
+    pub sd: u16,
+    pub bead_count: u8,
+}
+
+/// A single scan/processing step recorded in the `RunInfo` (code 300) field.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RunInfoBlock {
+    pub run_time: String,
+    pub block_type: String,
+    pub block_pars: String,
+    pub block_code: String,
+    pub code_version: String,
+}
+
 #[cfg(test)]
 mod tests {
     use std::{error::Error, path::Path};